@@ -0,0 +1,173 @@
+/// The computed-values stage: resolves each `StyledNode`'s specified values
+/// into absolute, inheritance-aware values by walking the tree alongside the
+/// parent's already-computed values.
+use crate::{ css::{Color, Unit, Value}, style::{PropertyMap, StyledNode} };
+use std::collections::HashMap;
+
+pub type ComputedValues = HashMap<String, Value>;
+
+/// Properties that fall back to the parent's computed value when the
+/// element itself doesn't specify them.
+const INHERITED_PROPERTIES: &[&str] = &["color", "font-size", "font-family", "line-height", "visibility"];
+
+const DEFAULT_FONT_SIZE_PX: f32 = 16.0;
+
+/// Computes a single node's values: inherited properties default to the
+/// parent's computed value, `inherit` forces that fallback explicitly,
+/// `initial` forces the property's defined default, and percentage lengths
+/// are resolved against the relevant parent length.
+pub fn computed_values(node: &StyledNode, parent: Option<&ComputedValues>) -> ComputedValues {
+    let mut computed = ComputedValues::new();
+
+    for property in INHERITED_PROPERTIES {
+        let value = match parent.and_then(|p| p.get(*property)) {
+            Some(value) => value.clone(),
+            None => initial_value(property),
+        };
+        computed.insert((*property).to_string(), value);
+    }
+
+    for (name, value) in specified(node) {
+        let resolved = match value {
+            Value::Keyword(keyword) if keyword == "inherit" => {
+                parent.and_then(|p| p.get(name)).cloned().unwrap_or_else(|| initial_value(name))
+            }
+            Value::Keyword(keyword) if keyword == "initial" => initial_value(name),
+            Value::Length(amount, Unit::Percent) => resolve_percentage(name, *amount, parent),
+            other => other.clone(),
+        };
+
+        computed.insert(name.clone(), resolved);
+    }
+
+    computed
+}
+
+fn specified<'a>(node: &'a StyledNode<'a>) -> &'a PropertyMap {
+    &node.specified_values
+}
+
+/// The CSS-defined default for a property, used when it is set to `initial`
+/// or when an `inherit` is requested but there is no parent to inherit from.
+/// Properties without a listed default keep the `initial` keyword as-is.
+fn initial_value(property: &str) -> Value {
+    match property {
+        "color" => Value::ColorValue(Color::new(0, 0, 0, 255)),
+        "font-size" => Value::Length(DEFAULT_FONT_SIZE_PX, Unit::Px),
+        "font-family" => Value::Keyword("serif".to_string()),
+        "line-height" => Value::Keyword("normal".to_string()),
+        "visibility" => Value::Keyword("visible".to_string()),
+        _ => Value::Keyword("initial".to_string()),
+    }
+}
+
+/// Resolves a percentage length against the parent length it's relative to.
+/// Only `font-size` is resolved (against the parent's font-size); percentages
+/// on every other property are left as-is.
+fn resolve_percentage(property: &str, amount: f32, parent: Option<&ComputedValues>) -> Value {
+    match property {
+        "font-size" => {
+            let parent_px = parent
+                .and_then(|p| p.get("font-size"))
+                .and_then(as_px)
+                .unwrap_or(DEFAULT_FONT_SIZE_PX);
+
+            Value::Length(parent_px * amount / 100.0, Unit::Px)
+        }
+        _ => Value::Length(amount, Unit::Percent),
+    }
+}
+
+fn as_px(value: &Value) -> Option<f32> {
+    match value {
+        Value::Length(amount, Unit::Px) => Some(*amount),
+        _ => None,
+    }
+}
+
+/// A `StyledNode` paired with its fully-resolved computed values, built by
+/// walking the styled tree top-down so each node can see its parent's
+/// already-computed values.
+#[derive(Debug)]
+pub struct ComputedNode<'a> {
+    pub node: &'a StyledNode<'a>,
+    pub computed: ComputedValues,
+    pub children: Vec<ComputedNode<'a>>,
+}
+
+pub fn computed_tree<'a>(node: &'a StyledNode<'a>, parent: Option<&ComputedValues>) -> ComputedNode<'a> {
+    let computed = computed_values(node, parent);
+    let children = node.children.iter().map(|child| computed_tree(child, Some(&computed))).collect();
+
+    ComputedNode { node, computed, children }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{css::Parser as CssParser, dom::Parser as DomParser, style::{style_tree, Stylist}};
+
+    #[test]
+    fn inherited_property_falls_back_to_initial_value_with_no_parent_and_no_rule() {
+        let html = DomParser::parse("<html></html>".to_string());
+        let (sheet, errors) = CssParser::parse("".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let root = computed_tree(&tree, None);
+
+        assert_eq!(root.computed.get("color"), Some(&Value::ColorValue(Color::new(0, 0, 0, 255))));
+    }
+
+    #[test]
+    fn child_inherits_parent_computed_value_when_unspecified() {
+        let html = DomParser::parse("<div><p>hi</p></div>".to_string());
+        let (sheet, errors) = CssParser::parse("div { color: #ff0000; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let root = computed_tree(&tree, None);
+        let p = &root.children[0];
+
+        assert_eq!(p.computed.get("color"), Some(&Value::ColorValue(Color::new(255, 0, 0, 255))));
+    }
+
+    #[test]
+    fn explicit_inherit_keyword_forces_parent_value() {
+        let html = DomParser::parse("<div><p>hi</p></div>".to_string());
+        let (sheet, errors) = CssParser::parse("div { color: #ff0000; } p { color: inherit; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let root = computed_tree(&tree, None);
+        let p = &root.children[0];
+
+        assert_eq!(p.computed.get("color"), Some(&Value::ColorValue(Color::new(255, 0, 0, 255))));
+    }
+
+    #[test]
+    fn explicit_initial_keyword_resets_to_default_despite_inherited_value() {
+        let html = DomParser::parse("<div><p>hi</p></div>".to_string());
+        let (sheet, errors) = CssParser::parse("div { color: #ff0000; } p { color: initial; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let root = computed_tree(&tree, None);
+        let p = &root.children[0];
+
+        assert_eq!(p.computed.get("color"), Some(&Value::ColorValue(Color::new(0, 0, 0, 255))));
+    }
+
+    #[test]
+    fn percentage_font_size_resolves_against_parent_font_size() {
+        let html = DomParser::parse("<div><p>hi</p></div>".to_string());
+        let (sheet, errors) = CssParser::parse("div { font-size: 20px; } p { font-size: 50%; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let root = computed_tree(&tree, None);
+        let p = &root.children[0];
+
+        assert_eq!(p.computed.get("font-size"), Some(&Value::Length(10.0, Unit::Px)));
+    }
+}
@@ -1,17 +1,68 @@
+use std::rc::Rc;
+
 #[derive(Debug)]
 pub struct StyleSheet {
+    pub origin: StylesheetOrigin,
     pub rules: Vec<Rule>,
+    /// A lower-priority sheet this one layers over, e.g. a bundled
+    /// user-agent default sheet sitting beneath a page's author styles.
+    pub parent: Option<Rc<StyleSheet>>,
+}
+
+impl StyleSheet {
+    /// Parses `source` as a stylesheet layered over `base`: `base`'s rules
+    /// act as a lower-priority fallback layer, so a page stylesheet can
+    /// override a bundled default without the caller manually concatenating
+    /// rule lists.
+    pub fn with_parent(base: Rc<StyleSheet>, source: String) -> (StyleSheet, Vec<ParseError>) {
+        let (mut sheet, errors) = Parser::parse_with_origin(source, StylesheetOrigin::Author);
+        sheet.parent = Some(base);
+        (sheet, errors)
+    }
+
+    /// This sheet's own rules, followed by its parent's (and so on up the
+    /// chain), each paired with its layer depth (`0` = this sheet, increasing
+    /// with each parent) so callers can rank a rule's originating layer in
+    /// the cascade.
+    pub fn all_rules(&self) -> Box<dyn Iterator<Item = (usize, &Rule)> + '_> {
+        self.all_rules_from_depth(0)
+    }
+
+    fn all_rules_from_depth(&self, depth: usize) -> Box<dyn Iterator<Item = (usize, &Rule)> + '_> {
+        match &self.parent {
+            Some(parent) => {
+                Box::new(self.rules.iter().map(move |rule| (depth, rule)).chain(parent.all_rules_from_depth(depth + 1)))
+            }
+            None => Box::new(self.rules.iter().map(move |rule| (depth, rule))),
+        }
+    }
+}
+
+/// Where a stylesheet came from, in increasing cascade priority order:
+/// `UserAgent` default styles lose to `User` styles, which lose to `Author`
+/// (page) styles, all else being equal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StylesheetOrigin {
+    UserAgent,
+    User,
+    Author,
 }
 
 #[derive(Debug)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    pub origin: StylesheetOrigin,
+    /// Position of this rule within its stylesheet's source, in parse order.
+    /// Used as the final cascade tie-break once origin/importance and
+    /// specificity are equal.
+    pub source_index: usize,
 }
 
 #[derive(Debug)]
 pub enum Selector {
     Simple(SimpleSelector),
+    Complex(ComplexSelector),
 }
 
 #[derive(Debug)]
@@ -21,26 +72,51 @@ pub struct SimpleSelector {
     pub class: Vec<String>,
 }
 
+/// A chain of `SimpleSelector`s joined by combinators, e.g. `div.box > p + span`.
+/// `compounds` is stored in source (left-to-right) order, and `combinators[i]`
+/// is the combinator joining `compounds[i]` and `compounds[i + 1]`, so
+/// `compounds.len() == combinators.len() + 1` and the rightmost compound
+/// (the one matched against the candidate element) is always `compounds.last()`.
+#[derive(Debug)]
+pub struct ComplexSelector {
+    pub compounds: Vec<SimpleSelector>,
+    pub combinators: Vec<Combinator>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Combinator {
+    /// ` ` - any ancestor
+    Descendant,
+    /// `>` - immediate parent
+    Child,
+    /// `+` - immediately preceding sibling
+    AdjacentSibling,
+    /// `~` - any preceding sibling
+    GeneralSibling,
+}
+
 #[derive(Debug)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    /// Set when the declaration is suffixed with `!important`.
+    pub important: bool,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Keyword(String),
     Length(f32, Unit),
     ColorValue(Color),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Unit {
     Px,
     Percent
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Color {
     r: u8,
     g: u8,
@@ -48,20 +124,50 @@ pub struct Color {
     a: u8,
 }
 
+impl Color {
+    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+}
+
+/// An error recovered from during parsing: the input between `start` and
+/// `end` was discarded rather than aborting the whole parse.
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
 pub struct Parser {
     pub pos: usize,
     pub input: String,
+    origin: StylesheetOrigin,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
-    pub fn parse(source: String) -> StyleSheet {
+    /// Parses a stylesheet, recovering from malformed rules/declarations
+    /// instead of aborting: anything dropped is reported in the returned
+    /// `Vec<ParseError>` alongside the rules that parsed successfully.
+    pub fn parse(source: String) -> (StyleSheet, Vec<ParseError>) {
+        Parser::parse_with_origin(source, StylesheetOrigin::Author)
+    }
+
+    pub fn parse_with_origin(source: String, origin: StylesheetOrigin) -> (StyleSheet, Vec<ParseError>) {
         let mut parser = Parser {
             pos: 0,
             input: source,
+            origin,
+            errors: vec![],
         };
-        StyleSheet {
-            rules: parser.parse_rules(),
-        }
+        let rules = parser.parse_rules();
+
+        (StyleSheet { origin, rules, parent: None }, parser.errors)
+    }
+
+    fn report_error(&mut self, message: impl Into<String>, start: usize) {
+        self.errors.push(ParseError { message: message.into(), start, end: self.pos });
     }
 
     fn parse_rules(&mut self) -> Vec<Rule> {
@@ -72,7 +178,10 @@ impl Parser {
                 break;
             }
 
-            rules.push(self.parse_rule());
+            let source_index = rules.len();
+            if let Some(rule) = self.parse_rule(source_index) {
+                rules.push(rule);
+            }
         }
 
         rules
@@ -148,17 +257,92 @@ impl Parser {
         selector
     }
 
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
+    fn parse_rule(&mut self, source_index: usize) -> Option<Rule> {
+        let selectors = self.parse_selectors()?;
+
+        Some(Rule {
+            selectors,
             declarations: self.parse_declarations(),
+            origin: self.origin,
+            source_index,
+        })
+    }
+
+    /// Skips to this rule's closing `}` (tracking nested braces), discarding
+    /// everything in between. Used after a malformed prelude or block so the
+    /// next rule can be parsed cleanly.
+    fn recover_rule(&mut self) {
+        while !self.eof() && self.next_char() != Some('{') {
+            self.consume_char();
+        }
+
+        if self.eof() {
+            return;
+        }
+        self.consume_char(); // the '{'
+
+        let mut depth = 1;
+        while !self.eof() && depth > 0 {
+            match self.consume_char() {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// Parses the combinator (if any) joining the compound just parsed to the
+    /// next one: an explicit `>`/`+`/`~`, or `Descendant` when the two compounds
+    /// are separated only by whitespace. Returns `None` once the selector list
+    /// runs out of compounds (next char is `,`, `{`, or eof).
+    fn parse_combinator(&mut self) -> Option<Combinator> {
+        let had_whitespace = !self.consume_whitespace().is_empty();
+
+        match self.next_char() {
+            Some('>') => {
+                self.consume_char();
+                self.consume_whitespace();
+                Some(Combinator::Child)
+            }
+            Some('+') => {
+                self.consume_char();
+                self.consume_whitespace();
+                Some(Combinator::AdjacentSibling)
+            }
+            Some('~') => {
+                self.consume_char();
+                self.consume_whitespace();
+                Some(Combinator::GeneralSibling)
+            }
+            Some(',') | Some('{') | None => None,
+            Some(_) if had_whitespace => Some(Combinator::Descendant),
+            _ => None,
         }
     }
 
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    /// Parses one full selector: a single compound, or a chain of compounds
+    /// joined by combinators (e.g. `ul.nav > li.active`).
+    fn parse_selector(&mut self) -> Selector {
+        let mut compounds = vec![self.parse_simple_selector()];
+        let mut combinators = vec![];
+
+        while let Some(combinator) = self.parse_combinator() {
+            combinators.push(combinator);
+            compounds.push(self.parse_simple_selector());
+        }
+
+        if combinators.is_empty() {
+            Selector::Simple(compounds.pop().unwrap())
+        } else {
+            Selector::Complex(ComplexSelector { compounds, combinators })
+        }
+    }
+
+    fn parse_selectors(&mut self) -> Option<Vec<Selector>> {
+        let start = self.pos;
         let mut selectors = vec![];
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
+            selectors.push(self.parse_selector());
             self.consume_whitespace();
 
             match self.next_char() {
@@ -170,13 +354,29 @@ impl Parser {
                     // start of declarations - break. Consume char for use in inline styles (which do not have brackets).
                     self.consume_char();
                     break
-                }, 
-                c => panic!("Unexpected character {:?} in selector list", c),
+                },
+                c => {
+                    self.report_error(format!("unexpected character {:?} in selector list", c), start);
+                    self.recover_rule();
+                    return None;
+                }
             }
         }
 
         selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        selectors
+        Some(selectors)
+    }
+
+    /// Parses a bare declaration list with no selector/braces, e.g. the
+    /// contents of a `style="..."` attribute. Always treated as author origin.
+    pub fn parse_inline_declarations(source: String) -> Vec<Declaration> {
+        let mut parser = Parser {
+            pos: 0,
+            input: source,
+            origin: StylesheetOrigin::Author,
+            errors: vec![],
+        };
+        parser.parse_declarations()
     }
 
     pub fn parse_declarations(&mut self) -> Vec<Declaration> {
@@ -184,77 +384,395 @@ impl Parser {
         loop {
             self.consume_whitespace();
 
+            if self.eof() {
+                break;
+            }
             if self.next_char() == Some('}') {
                 self.consume_char();
                 break;
             }
 
-            declarations.push(self.parse_declaration());
+            if let Some(declaration) = self.parse_declaration() {
+                declarations.push(declaration);
+            }
         }
 
         declarations
     }
 
-    fn parse_declaration(&mut self) -> Declaration {
+    /// Parses one `name: value;` declaration, recovering instead of
+    /// panicking on malformed input: on any syntax error this reports a
+    /// `ParseError` and consumes up to (and including) the next `;` - or up
+    /// to but not including an unbalanced `}`, which the caller treats as
+    /// the end of the rule - dropping just that one declaration.
+    fn parse_declaration(&mut self) -> Option<Declaration> {
+        let start = self.pos;
         let name = self.parse_identifier();
+
+        if name.is_empty() {
+            self.report_error("expected a property name", start);
+            self.recover_declaration();
+            return None;
+        }
+        self.consume_whitespace();
+
+        if self.next_char() != Some(':') {
+            self.report_error(format!("expected ':' after property name '{}'", name), start);
+            self.recover_declaration();
+            return None;
+        }
+        self.consume_char();
+        self.consume_whitespace();
+
+        let value = match self.parse_value() {
+            Some(value) => value,
+            None => {
+                self.report_error(format!("invalid value for property '{}'", name), start);
+                self.recover_declaration();
+                return None;
+            }
+        };
         self.consume_whitespace();
 
-        assert_eq!(self.consume_char(), ':');
+        let important = match self.parse_important() {
+            Some(important) => important,
+            None => {
+                self.report_error(format!("expected 'important' after '!' in declaration '{}'", name), start);
+                self.recover_declaration();
+                return None;
+            }
+        };
         self.consume_whitespace();
 
-        let value = self.parse_value();
+        if self.next_char() != Some(';') {
+            self.report_error(format!("expected ';' to terminate declaration '{}'", name), start);
+            self.recover_declaration();
+            return None;
+        }
+        self.consume_char();
+
+        Some(Declaration { name, value, important })
+    }
+
+    /// Consumes up to and including the next top-level `;`, or up to (but
+    /// not past) an unbalanced `}`, so the enclosing declaration list can
+    /// keep going after dropping one bad declaration.
+    fn recover_declaration(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.next_char() {
+                None => return,
+                Some('}') if depth == 0 => return,
+                Some('{') => {
+                    depth += 1;
+                    self.consume_char();
+                }
+                Some('}') => {
+                    depth -= 1;
+                    self.consume_char();
+                }
+                Some(';') if depth == 0 => {
+                    self.consume_char();
+                    return;
+                }
+                Some(_) => {
+                    self.consume_char();
+                }
+            }
+        }
+    }
 
-        assert_eq!(self.consume_char(), ';');
+    /// Consumes a trailing `!important` if present, returning whether one was
+    /// found, or `None` if a `!` is followed by anything other than `important`.
+    fn parse_important(&mut self) -> Option<bool> {
+        if self.next_char() != Some('!') {
+            return Some(false);
+        }
 
-        Declaration { name, value }
+        self.consume_char();
+        self.consume_whitespace();
+        if self.parse_identifier().eq_ignore_ascii_case("important") { Some(true) } else { None }
     }
 
-    fn parse_value(&mut self) -> Value {
+    fn parse_value(&mut self) -> Option<Value> {
         match self.next_char() {
             Some('0'..='9') => self.parse_length(),
-            Some('#') => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier()),
+            Some('#') => self.parse_hex_color(),
+            Some(_) => self.parse_identifier_value(),
+            None => None,
         }
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    /// Parses a bare identifier value: `rgb()`/`rgba()`/`hsl()`/`hsla()`
+    /// function calls, a named color, or - if it's neither - a plain keyword.
+    fn parse_identifier_value(&mut self) -> Option<Value> {
+        let identifier = self.parse_identifier();
+        if identifier.is_empty() {
+            return None;
+        }
+
+        let lower = identifier.to_ascii_lowercase();
+        match &*lower {
+            "rgb" | "rgba" if self.next_char() == Some('(') => self.parse_rgb_function(),
+            "hsl" | "hsla" if self.next_char() == Some('(') => self.parse_hsl_function(),
+            _ => match named_color(&lower) {
+                Some(color) => Some(Value::ColorValue(color)),
+                None => Some(Value::Keyword(identifier)),
+            },
+        }
     }
 
-    fn parse_float(&mut self) -> f32 {
+    fn parse_length(&mut self) -> Option<Value> {
+        let number = self.parse_float()?;
+        let unit = self.parse_unit()?;
+        Some(Value::Length(number, unit))
+    }
+
+    /// Parses a number, with an optional leading `-` (needed for `rgb()`/
+    /// `hsl()` components that are out of range before clamping/wrapping,
+    /// e.g. `rgb(999, -10, 20)` or `hsl(-30, 50%, 50%)`).
+    fn parse_float(&mut self) -> Option<f32> {
+        let negative = self.next_char() == Some('-');
+        if negative {
+            self.consume_char();
+        }
+
         let s = self.consume_while(|c| match c {
             '0'..='9' | '.' => true,
             _ => false,
         });
 
-        s.parse().unwrap()
+        let magnitude: f32 = s.parse().ok()?;
+        Some(if negative { -magnitude } else { magnitude })
     }
 
-    fn parse_unit(&mut self) -> Unit {
+    fn parse_unit(&mut self) -> Option<Unit> {
         match &*self.parse_identifier().to_ascii_lowercase() {
-            "px" => Unit::Px,
-            "%" => Unit::Percent,
-            c => panic!("Unrecognised unit: {}", c),
+            "px" => Some(Unit::Px),
+            "%" => Some(Unit::Percent),
+            _ => None,
         }
     }
 
-    fn parse_color(&mut self) -> Value {
-        assert_eq!(self.consume_char(), '#');
+    /// Parses `#RGB`, `#RGBA`, `#RRGGBB`, or `#RRGGBBAA`, expanding each
+    /// 3/4-digit nibble by duplication (`#0a3` -> `#00aa33`) per the CSS spec.
+    fn parse_hex_color(&mut self) -> Option<Value> {
+        self.consume_char(); // '#', guaranteed present by the caller's match
+        let digits = self.consume_while(|c| c.is_ascii_hexdigit());
+
+        let color = match digits.len() {
+            3 => Color::new(
+                expand_hex_nibble(&digits[0..1])?,
+                expand_hex_nibble(&digits[1..2])?,
+                expand_hex_nibble(&digits[2..3])?,
+                255,
+            ),
+            4 => Color::new(
+                expand_hex_nibble(&digits[0..1])?,
+                expand_hex_nibble(&digits[1..2])?,
+                expand_hex_nibble(&digits[2..3])?,
+                expand_hex_nibble(&digits[3..4])?,
+            ),
+            6 => Color::new(
+                hex_byte(&digits[0..2])?,
+                hex_byte(&digits[2..4])?,
+                hex_byte(&digits[4..6])?,
+                255,
+            ),
+            8 => Color::new(
+                hex_byte(&digits[0..2])?,
+                hex_byte(&digits[2..4])?,
+                hex_byte(&digits[4..6])?,
+                hex_byte(&digits[6..8])?,
+            ),
+            _ => return None,
+        };
 
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255,
-        })
+        Some(Value::ColorValue(color))
+    }
+
+    /// Parses `rgb(r, g, b)` or `rgba(r, g, b, a)`; the alpha component is
+    /// optional so both forms share this parser.
+    fn parse_rgb_function(&mut self) -> Option<Value> {
+        self.consume_char(); // '('
+
+        let r = self.parse_rgb_component()?;
+        let g = self.parse_rgb_component()?;
+        let b = self.parse_rgb_component()?;
+        let a = self.parse_optional_alpha_component()?;
+
+        self.consume_whitespace();
+        if self.next_char() != Some(')') {
+            return None;
+        }
+        self.consume_char();
+
+        Some(Value::ColorValue(Color::new(r, g, b, a)))
+    }
+
+    /// Parses one `rgb()`/`rgba()` channel (0-255) and its trailing comma.
+    fn parse_rgb_component(&mut self) -> Option<u8> {
+        self.consume_whitespace();
+        let n = self.parse_float()?;
+        self.consume_whitespace();
+        if self.next_char() == Some(',') {
+            self.consume_char();
+        }
+        Some(n.round().clamp(0.0, 255.0) as u8)
+    }
+
+    /// Parses `hsl(h, s%, l%)` or `hsla(h, s%, l%, a)`, converting to RGB.
+    fn parse_hsl_function(&mut self) -> Option<Value> {
+        self.consume_char(); // '('
+
+        let h = self.parse_hue_component()?;
+        let s = self.parse_percentage_component()?;
+        let l = self.parse_percentage_component()?;
+        let a = self.parse_optional_alpha_component()?;
+
+        self.consume_whitespace();
+        if self.next_char() != Some(')') {
+            return None;
+        }
+        self.consume_char();
+
+        let (r, g, b) = hsl_to_rgb(h, s, l);
+        Some(Value::ColorValue(Color::new(r, g, b, a)))
+    }
+
+    /// Parses the hue component of `hsl()`: a number in degrees, with an
+    /// optional unit (e.g. `120deg`) that this engine ignores.
+    fn parse_hue_component(&mut self) -> Option<f32> {
+        self.consume_whitespace();
+        let n = self.parse_float()?;
+        if self.next_char().is_some_and(|c| c.is_alphabetic()) {
+            self.parse_identifier();
+        }
+        self.consume_whitespace();
+        if self.next_char() == Some(',') {
+            self.consume_char();
+        }
+        Some(((n % 360.0) + 360.0) % 360.0)
+    }
+
+    /// Parses a `N%` component of `hsl()` (saturation or lightness) as a 0.0-1.0 fraction.
+    fn parse_percentage_component(&mut self) -> Option<f32> {
+        self.consume_whitespace();
+        let n = self.parse_float()?;
+        if self.next_char() == Some('%') {
+            self.consume_char();
+        }
+        self.consume_whitespace();
+        if self.next_char() == Some(',') {
+            self.consume_char();
+        }
+        Some(n.clamp(0.0, 100.0) / 100.0)
+    }
+
+    /// Parses the optional trailing alpha component shared by `rgba()` and
+    /// `hsla()`, written as a 0.0-1.0 fraction and returned scaled to 0-255.
+    /// Returns fully opaque (255) if absent.
+    fn parse_optional_alpha_component(&mut self) -> Option<u8> {
+        self.consume_whitespace();
+        if self.next_char() == Some(')') {
+            return Some(255);
+        }
+
+        let alpha = self.parse_float()?;
+        self.consume_whitespace();
+        Some((alpha.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+}
+
+fn expand_hex_nibble(nibble: &str) -> Option<u8> {
+    Some(u8::from_str_radix(nibble, 16).ok()? * 17)
+}
+
+fn hex_byte(pair: &str) -> Option<u8> {
+    u8::from_str_radix(pair, 16).ok()
+}
+
+/// Converts an HSL color (`h` in degrees, `s`/`l` as 0.0-1.0 fractions) to RGB.
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
+    if s == 0.0 {
+        let v = (l * 255.0).round() as u8;
+        return (v, v, v);
     }
 
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos..self.pos + 2];
+    let q = if l < 0.5 { l * (1.0 + s) } else { l + s - l * s };
+    let p = 2.0 * l - q;
+    let h = h / 360.0;
+
+    let to_byte = |t: f32| (hue_to_rgb(p, q, t) * 255.0).round() as u8;
 
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+    (to_byte(h + 1.0 / 3.0), to_byte(h), to_byte(h - 1.0 / 3.0))
+}
+
+fn hue_to_rgb(p: f32, q: f32, t: f32) -> f32 {
+    let mut t = t;
+    if t < 0.0 {
+        t += 1.0;
+    }
+    if t > 1.0 {
+        t -= 1.0;
     }
+
+    if t < 1.0 / 6.0 {
+        p + (q - p) * 6.0 * t
+    } else if t < 1.0 / 2.0 {
+        q
+    } else if t < 2.0 / 3.0 {
+        p + (q - p) * (2.0 / 3.0 - t) * 6.0
+    } else {
+        p
+    }
+}
+
+/// Looks up a CSS named color (case-insensitive, expects a lowercased
+/// input). Not exhaustive - covers `transparent` plus the common named
+/// colors rather than the full CSS Color Module table.
+fn named_color(name: &str) -> Option<Color> {
+    Some(match name {
+        "transparent" => Color::new(0, 0, 0, 0),
+        "black" => Color::new(0, 0, 0, 255),
+        "white" => Color::new(255, 255, 255, 255),
+        "red" => Color::new(255, 0, 0, 255),
+        "green" => Color::new(0, 128, 0, 255),
+        "lime" => Color::new(0, 255, 0, 255),
+        "blue" => Color::new(0, 0, 255, 255),
+        "yellow" => Color::new(255, 255, 0, 255),
+        "cyan" | "aqua" => Color::new(0, 255, 255, 255),
+        "magenta" | "fuchsia" => Color::new(255, 0, 255, 255),
+        "gray" | "grey" => Color::new(128, 128, 128, 255),
+        "silver" => Color::new(192, 192, 192, 255),
+        "maroon" => Color::new(128, 0, 0, 255),
+        "olive" => Color::new(128, 128, 0, 255),
+        "navy" => Color::new(0, 0, 128, 255),
+        "purple" => Color::new(128, 0, 128, 255),
+        "teal" => Color::new(0, 128, 128, 255),
+        "orange" => Color::new(255, 165, 0, 255),
+        "pink" => Color::new(255, 192, 203, 255),
+        "brown" => Color::new(165, 42, 42, 255),
+        "gold" => Color::new(255, 215, 0, 255),
+        "indigo" => Color::new(75, 0, 130, 255),
+        "violet" => Color::new(238, 130, 238, 255),
+        "coral" => Color::new(255, 127, 80, 255),
+        "salmon" => Color::new(250, 128, 114, 255),
+        "khaki" => Color::new(240, 230, 140, 255),
+        "turquoise" => Color::new(64, 224, 208, 255),
+        "tan" => Color::new(210, 180, 140, 255),
+        "beige" => Color::new(245, 245, 220, 255),
+        "lavender" => Color::new(230, 230, 250, 255),
+        "crimson" => Color::new(220, 20, 60, 255),
+        "chocolate" => Color::new(210, 105, 30, 255),
+        "plum" => Color::new(221, 160, 221, 255),
+        "orchid" => Color::new(218, 112, 214, 255),
+        "skyblue" => Color::new(135, 206, 235, 255),
+        "slategray" | "slategrey" => Color::new(112, 128, 144, 255),
+        "tomato" => Color::new(255, 99, 71, 255),
+        "wheat" => Color::new(245, 222, 179, 255),
+        _ => return None,
+    })
 }
 
 fn valid_identifier_char(c: char) -> bool {
@@ -272,12 +790,134 @@ impl Selector {
     /// IDs have highest priority, then classes, then tags
     /// Returns a tuple of (ID count, class count, tag count)
     pub fn specificity(&self) -> Specificity {
-        let Selector::Simple(ref simple) = *self;
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            Selector::Complex(ref complex) => {
+                complex.compounds.iter().fold((0, 0, 0), |(ids, classes, tags), compound| {
+                    let (c_ids, c_classes, c_tags) = compound.specificity();
+                    (ids + c_ids, classes + c_classes, tags + c_tags)
+                })
+            }
+        }
+    }
+}
 
-        let id_count = simple.id.iter().count();
-        let class_count = simple.class.len();
-        let tag_count = simple.tag_name.iter().count();
+impl SimpleSelector {
+    fn specificity(&self) -> Specificity {
+        let id_count = self.id.iter().count();
+        let class_count = self.class.len();
+        let tag_count = self.tag_name.iter().count();
 
         (id_count, class_count, tag_count)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dom::Parser as DomParser, style::{style_tree, Stylist}};
+
+    /// `Stylist::new` buckets rules by the rightmost compound's id, class, or
+    /// tag name; unrelated rules in other buckets must not leak into an
+    /// element's matches just because they're all in the same stylesheet.
+    #[test]
+    fn stylist_prefilters_by_id_class_and_tag_independently() {
+        let html = DomParser::parse("<p id=\"x\" class=\"y\">hi</p>".to_string());
+        let (sheet, errors) = Parser::parse(
+            "#other { color: #ff0000; } .other { color: #ff0000; } span { color: #ff0000; } #x { color: #00ff00; }"
+                .to_string(),
+        );
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+
+        assert_eq!(tree.specified_values.get("color"), Some(&Value::ColorValue(Color::new(0, 255, 0, 255))));
+    }
+
+    #[test]
+    fn malformed_declaration_is_dropped_without_losing_its_siblings() {
+        let (sheet, errors) = Parser::parse("p { invalid 1px; font-size: 12px; }".to_string());
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(sheet.rules.len(), 1);
+        assert_eq!(sheet.rules[0].declarations.len(), 1);
+        assert_eq!(sheet.rules[0].declarations[0].name, "font-size");
+    }
+
+    #[test]
+    fn malformed_rule_is_dropped_without_losing_surrounding_rules() {
+        let (sheet, errors) =
+            Parser::parse("p { color: #ff0000; } .foo) { color: #00ff00; } div { color: #0000ff; }".to_string());
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(sheet.rules.len(), 2);
+
+        let tag = |rule: &Rule| match &rule.selectors[0] {
+            Selector::Simple(simple) => simple.tag_name.clone().unwrap(),
+            Selector::Complex(_) => panic!("expected a simple selector"),
+        };
+        assert_eq!(tag(&sheet.rules[0]), "p");
+        assert_eq!(tag(&sheet.rules[1]), "div");
+    }
+
+    fn parse_color_value(expr: &str) -> Value {
+        let (sheet, errors) = Parser::parse(format!("x {{ color: {}; }}", expr));
+        assert!(errors.is_empty(), "unexpected errors for {:?}: {:?}", expr, errors);
+        sheet.rules[0].declarations[0].value.clone()
+    }
+
+    #[test]
+    fn short_and_long_hex_forms_expand_nibbles_and_parse_alpha() {
+        assert_eq!(parse_color_value("#0af"), Value::ColorValue(Color::new(0, 170, 255, 255)));
+        assert_eq!(parse_color_value("#0af8"), Value::ColorValue(Color::new(0, 170, 255, 136)));
+        assert_eq!(parse_color_value("#00aaff"), Value::ColorValue(Color::new(0, 170, 255, 255)));
+        assert_eq!(parse_color_value("#00aaff80"), Value::ColorValue(Color::new(0, 170, 255, 128)));
+    }
+
+    #[test]
+    fn rgb_and_rgba_functions_parse() {
+        assert_eq!(parse_color_value("rgb(0, 170, 255)"), Value::ColorValue(Color::new(0, 170, 255, 255)));
+        assert_eq!(parse_color_value("rgba(0, 170, 255, 0.5)"), Value::ColorValue(Color::new(0, 170, 255, 128)));
+    }
+
+    #[test]
+    fn hsl_and_hsla_functions_convert_to_rgb() {
+        assert_eq!(parse_color_value("hsl(0, 100%, 50%)"), Value::ColorValue(Color::new(255, 0, 0, 255)));
+        assert_eq!(parse_color_value("hsla(0, 100%, 50%, 0.5)"), Value::ColorValue(Color::new(255, 0, 0, 128)));
+    }
+
+    #[test]
+    fn named_colors_and_transparent_resolve() {
+        assert_eq!(parse_color_value("red"), Value::ColorValue(Color::new(255, 0, 0, 255)));
+        assert_eq!(parse_color_value("transparent"), Value::ColorValue(Color::new(0, 0, 0, 0)));
+    }
+
+    #[test]
+    fn rgb_out_of_range_components_are_clamped_instead_of_failing_the_declaration() {
+        assert_eq!(parse_color_value("rgb(999, -10, 20)"), Value::ColorValue(Color::new(255, 0, 20, 255)));
+    }
+
+    #[test]
+    fn hsl_negative_hue_wraps_instead_of_failing_the_declaration() {
+        let wrapped_hue = ((-30.0_f32 % 360.0) + 360.0) % 360.0;
+        let (r, g, b) = hsl_to_rgb(wrapped_hue, 0.5, 0.5);
+
+        assert_eq!(parse_color_value("hsl(-30, 50%, 50%)"), Value::ColorValue(Color::new(r, g, b, 255)));
+    }
+
+    #[test]
+    fn all_rules_yields_own_rules_before_parents_with_increasing_depth() {
+        let (base, base_errors) = Parser::parse_with_origin("p { color: #ff0000; }".to_string(), StylesheetOrigin::UserAgent);
+        assert!(base_errors.is_empty());
+        let (sheet, errors) = StyleSheet::with_parent(std::rc::Rc::new(base), "div { color: #00ff00; }".to_string());
+        assert!(errors.is_empty());
+
+        let tag = |rule: &Rule| match &rule.selectors[0] {
+            Selector::Simple(simple) => simple.tag_name.clone().unwrap(),
+            Selector::Complex(_) => panic!("expected a simple selector"),
+        };
+        let layers: Vec<(usize, String)> = sheet.all_rules().map(|(depth, rule)| (depth, tag(rule))).collect();
+
+        assert_eq!(layers, vec![(0, "div".to_string()), (1, "p".to_string())]);
+    }
+}
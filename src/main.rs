@@ -1,16 +1,29 @@
+pub mod computed;
 pub mod css;
 pub mod dom;
 pub mod style;
 
-use css::Parser as CSSParser;
+use css::{Parser as CSSParser, StylesheetOrigin};
 use dom::Parser as DomParser;
+use std::rc::Rc;
 
 fn main() {
   let html_res = DomParser::parse("<html><body><p class=\"silly\">Hello, world!</p><p style=\"name:inline-style;color: #FF0000;\">How are we?</p></body></html>".to_string());
   println!("{:?}", html_res);
   println!("\n");
 
-  let css_res = CSSParser::parse(
+  // A bundled user-agent default sheet, layered beneath the page's author
+  // styles so the page only needs to override what it cares about.
+  let (ua_res, ua_errors) = CSSParser::parse_with_origin(
+    "p { color: #000000; }".to_string(),
+    StylesheetOrigin::UserAgent,
+  );
+  if !ua_errors.is_empty() {
+    println!("UA stylesheet parse errors: {:?}", ua_errors);
+  }
+
+  let (css_res, css_errors) = css::StyleSheet::with_parent(
+    Rc::new(ua_res),
     "
       .silly { background-color: transparent; width: 100%; color: #0000FF; }
       .billy { color: #0000FF; }
@@ -20,8 +33,16 @@ fn main() {
   );
 
   println!("{:?}", css_res);
+  if !css_errors.is_empty() {
+    println!("CSS parse errors: {:?}", css_errors);
+  }
   println!("\n");
 
-  let tree = style::style_tree(&html_res, &css_res);
+  let stylist = style::Stylist::new(&css_res);
+  let tree = style::style_tree(&html_res, &stylist);
   println!("{:#?}", tree);
+  println!("\n");
+
+  let computed = computed::computed_tree(&tree, None);
+  println!("{:#?}", computed);
 }
@@ -1,23 +1,38 @@
 /// This module takes CSS rules and applies them to suitable dom elements
 
-use crate::{ css::{Parser as CssParser, Rule, Selector, SimpleSelector, Specificity, StyleSheet, Value}, dom::{ ElementData, Node, NodeType}};
+use crate::{ css::{Combinator, ComplexSelector, Declaration, Parser as CssParser, Rule, Selector, SimpleSelector, Specificity, StyleSheet, StylesheetOrigin, Value}, dom::{ ElementData, Node, NodeType}};
 use std::collections::HashMap;
 
-type PropertyMap = HashMap<String, Value>;
+pub(crate) type PropertyMap = HashMap<String, Value>;
 
 #[derive(Debug)]
 pub struct StyledNode<'a> {
-    children: Vec<StyledNode<'a>>,
-    node: &'a Node,
-    specified_values: PropertyMap,
+    pub(crate) children: Vec<StyledNode<'a>>,
+    pub(crate) node: &'a Node,
+    pub(crate) specified_values: PropertyMap,
 }
 
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+/// An ancestor of the element being matched, along with that ancestor's own
+/// preceding siblings - needed so a combinator chain that climbs past it
+/// (e.g. the `a` in `a + b c`) can keep resolving sibling combinators.
+struct AncestorContext<'a> {
+    element: &'a ElementData,
+    preceding_siblings: Vec<&'a ElementData>,
+}
+
+/// Everything the matcher needs to evaluate combinators: the element itself,
+/// its ancestor chain (nearest parent first), and its preceding siblings
+/// (nearest sibling first).
+struct ElementContext<'a> {
+    element: &'a ElementData,
+    ancestors: Vec<AncestorContext<'a>>,
+    preceding_siblings: Vec<&'a ElementData>,
+}
+
+fn matches(ctx: &ElementContext, selector: &Selector) -> bool {
     match *selector {
-        Selector::Simple(ref simple) => matches_simple_selector(elem, simple),
-        // _ => {
-        //     false
-        // }
+        Selector::Simple(ref simple) => matches_simple_selector(ctx.element, simple),
+        Selector::Complex(ref complex) => matches_complex_selector(ctx, complex),
     }
 }
 
@@ -45,36 +60,217 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
     true
 }
 
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
+/// Evaluates a complex (combinator-joined) selector right-to-left: the
+/// rightmost compound must match the candidate element, then each preceding
+/// compound must match somewhere up the ancestor/sibling chain depending on
+/// its combinator.
+fn matches_complex_selector(ctx: &ElementContext, complex: &ComplexSelector) -> bool {
+    let compound_count = complex.compounds.len();
+
+    match compound_count.checked_sub(1) {
+        Some(last) => {
+            if !matches_simple_selector(ctx.element, &complex.compounds[last]) {
+                return false;
+            }
+        }
+        None => return true,
+    }
+
+    let mut ancestors: &[AncestorContext] = &ctx.ancestors;
+    let mut siblings: &[&ElementData] = &ctx.preceding_siblings;
+
+    for i in (0..compound_count - 1).rev() {
+        let compound = &complex.compounds[i];
+
+        match complex.combinators[i] {
+            Combinator::Child => match ancestors.first() {
+                Some(parent) if matches_simple_selector(parent.element, compound) => {
+                    siblings = &parent.preceding_siblings;
+                    ancestors = &ancestors[1..];
+                }
+                _ => return false,
+            },
+            Combinator::Descendant => {
+                match ancestors.iter().position(|anc| matches_simple_selector(anc.element, compound)) {
+                    Some(idx) => {
+                        siblings = &ancestors[idx].preceding_siblings;
+                        ancestors = &ancestors[idx + 1..];
+                    }
+                    None => return false,
+                }
+            }
+            Combinator::AdjacentSibling => match siblings.split_first() {
+                Some((sibling, rest)) if matches_simple_selector(sibling, compound) => {
+                    siblings = rest;
+                }
+                _ => return false,
+            },
+            Combinator::GeneralSibling => {
+                match siblings.iter().position(|sibling| matches_simple_selector(sibling, compound)) {
+                    Some(idx) => siblings = &siblings[idx + 1..],
+                    None => return false,
+                }
+            }
+        }
+    }
+
+    true
+}
+
+/// A rule paired with the depth of the layered stylesheet it came from
+/// (`0` = the sheet passed to `Stylist::new`, increasing with each parent).
+type LayeredRule<'a> = (usize, &'a Rule);
+
+/// A pre-filtered index over a `StyleSheet`'s rules (including any parent
+/// layers it was built with `with_parent`), built once and reused for every
+/// element. Rules are bucketed by the rightmost simple selector's most
+/// selective key (id, then class, then tag name), so looking up candidates
+/// for an element only touches the handful of rules that could possibly
+/// match it instead of scanning every layer's rules.
+pub struct Stylist<'a> {
+    by_id: HashMap<String, Vec<LayeredRule<'a>>>,
+    by_class: HashMap<String, Vec<LayeredRule<'a>>>,
+    by_tag: HashMap<String, Vec<LayeredRule<'a>>>,
+    universal: Vec<LayeredRule<'a>>,
+}
+
+impl<'a> Stylist<'a> {
+    pub fn new(stylesheet: &'a StyleSheet) -> Stylist<'a> {
+        let mut stylist = Stylist {
+            by_id: HashMap::new(),
+            by_class: HashMap::new(),
+            by_tag: HashMap::new(),
+            universal: vec![],
+        };
+
+        for (depth, rule) in stylesheet.all_rules() {
+            for selector in &rule.selectors {
+                stylist.bucket(depth, rule, rightmost_compound(selector));
+            }
+        }
+
+        stylist
+    }
+
+    fn bucket(&mut self, depth: usize, rule: &'a Rule, compound: Option<&SimpleSelector>) {
+        match compound {
+            Some(simple) if simple.id.is_some() => {
+                self.by_id.entry(simple.id.clone().unwrap()).or_default().push((depth, rule));
+            }
+            Some(simple) if !simple.class.is_empty() => {
+                for class in &simple.class {
+                    self.by_class.entry(class.clone()).or_default().push((depth, rule));
+                }
+            }
+            Some(simple) if simple.tag_name.is_some() => {
+                self.by_tag.entry(simple.tag_name.clone().unwrap()).or_default().push((depth, rule));
+            }
+            _ => self.universal.push((depth, rule)),
+        }
+    }
+
+    /// Candidate rules that could possibly match `elem` - the small
+    /// pre-filtered set that `match_rule` then checks in full.
+    fn candidates(&self, elem: &ElementData) -> Vec<LayeredRule<'a>> {
+        let mut candidates = self.universal.clone();
+
+        if let Some(id) = elem.id() {
+            if let Some(rules) = self.by_id.get(id) {
+                candidates.extend(rules);
+            }
+        }
+
+        for class in elem.classes() {
+            if let Some(rules) = self.by_class.get(class) {
+                candidates.extend(rules);
+            }
+        }
+
+        if let Some(rules) = self.by_tag.get(&elem.tag_name) {
+            candidates.extend(rules);
+        }
+
+        candidates
+    }
+}
+
+fn rightmost_compound(selector: &Selector) -> Option<&SimpleSelector> {
+    match selector {
+        Selector::Simple(simple) => Some(simple),
+        Selector::Complex(complex) => complex.compounds.last(),
+    }
+}
+
+fn match_rule<'a>(ctx: &ElementContext, depth: usize, rule: &'a Rule) -> Option<MatchedRule<'a>> {
     // find highest-specificity (first) matching selector.
     rule.selectors.iter()
-    .find(|selector| matches(elem, *selector))
-    .map(|selector| (selector.specificity(), rule))
+    .find(|selector| matches(ctx, *selector))
+    .map(|selector| MatchedRule { specificity: selector.specificity(), depth, rule })
+}
+
+struct MatchedRule<'a> {
+    specificity: Specificity,
+    /// Layer depth of the stylesheet the rule came from (0 = the sheet
+    /// passed to `Stylist::new`, increasing with each parent layer).
+    depth: usize,
+    rule: &'a Rule,
 }
 
-type MatchedRule<'a> = (Specificity, &'a Rule);
+fn matching_rules<'a>(ctx: &ElementContext, stylist: &Stylist<'a>) -> Vec<MatchedRule<'a>> {
+    stylist.candidates(ctx.element).into_iter()
+        .filter_map(|(depth, rule)| match_rule(ctx, depth, rule))
+        .collect()
+}
+
+/// Where a declaration sits in the cascade, from lowest to highest
+/// precedence: origin/importance first, then specificity, then layer depth
+/// (a parent/base stylesheet is a lower-priority layer than the sheet
+/// layered over it), then source order. Sorting ascending by this key and
+/// applying declarations in order means the highest-precedence declaration
+/// for a property is the last one written, which is exactly what a
+/// `HashMap::insert` overwrite needs.
+type CascadeOrder = (u8, Specificity, usize, usize);
 
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a StyleSheet) -> Vec<MatchedRule<'a>> {
-    stylesheet.rules.iter().filter_map(|rule| match_rule(elem, rule)).collect()
+/// Ranks a declaration's origin+importance. Important declarations keep the
+/// same origin order as normal ones (author above user above user-agent),
+/// and an important declaration of any origin outranks every normal declaration.
+fn cascade_rank(origin: StylesheetOrigin, important: bool) -> u8 {
+    match (important, origin) {
+        (false, StylesheetOrigin::UserAgent) => 0,
+        (false, StylesheetOrigin::User) => 1,
+        (false, StylesheetOrigin::Author) => 2,
+        (true, StylesheetOrigin::UserAgent) => 3,
+        (true, StylesheetOrigin::User) => 4,
+        (true, StylesheetOrigin::Author) => 5,
+    }
 }
 
 /// Apply styles to a single element, returning the specified values
-fn specified_values(elem: &ElementData, stylesheet: &StyleSheet) -> PropertyMap {
+fn specified_values(ctx: &ElementContext, stylist: &Stylist) -> PropertyMap {
     let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
+    let matched = matching_rules(ctx, stylist);
 
-    // sort by Specificity
-    rules.sort_by(|&(a, _), (b, _)| a.cmp(&b));
-    for (_, rule) in rules {
-        for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
-        }
+    let mut declarations: Vec<(CascadeOrder, &Declaration)> = matched.iter()
+        .flat_map(|matched_rule| matched_rule.rule.declarations.iter().map(move |declaration| {
+            let order = (
+                cascade_rank(matched_rule.rule.origin, declaration.important),
+                matched_rule.specificity,
+                usize::MAX - matched_rule.depth,
+                matched_rule.rule.source_index,
+            );
+            (order, declaration)
+        }))
+        .collect();
+
+    // Lowest cascade precedence first, so later inserts win ties.
+    declarations.sort_by_key(|(order, _)| *order);
+    for (_, declaration) in declarations {
+        values.insert(declaration.name.clone(), declaration.value.clone());
     }
 
-    match elem.attributes.get("style") {
+    match ctx.element.attributes.get("style") {
         Some(inline_styles) => {
-            let mut inline_parser = CssParser { pos: 0, input: inline_styles.to_string() };
-            let declarations = inline_parser.parse_declarations();
+            let declarations = CssParser::parse_inline_declarations(inline_styles.to_string());
 
             for declaration in declarations {
                 values.insert(declaration.name, declaration.value);
@@ -86,13 +282,189 @@ fn specified_values(elem: &ElementData, stylesheet: &StyleSheet) -> PropertyMap
     values
 }
 
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a StyleSheet) -> StyledNode<'a> {
-    StyledNode {
-        children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect(),
-        node: root,
-        specified_values: match &root.node_type {
-            NodeType::Text(_) => HashMap::new(),
-            NodeType::Element(data) => specified_values(data, stylesheet)
+pub fn style_tree<'a>(root: &'a Node, stylist: &Stylist<'a>) -> StyledNode<'a> {
+    style_tree_with_context(root, stylist, &[], &[])
+}
+
+fn style_tree_with_context<'a>(
+    root: &'a Node,
+    stylist: &Stylist<'a>,
+    ancestors: &[AncestorContext<'a>],
+    preceding_siblings: &[&'a ElementData],
+) -> StyledNode<'a> {
+    let specified_values = match &root.node_type {
+        NodeType::Text(_) => HashMap::new(),
+        NodeType::Element(data) => {
+            let ctx = ElementContext {
+                element: data,
+                ancestors: clone_ancestors(ancestors),
+                preceding_siblings: preceding_siblings.to_vec(),
+            };
+            specified_values(&ctx, stylist)
+        }
+    };
+
+    let children = match &root.node_type {
+        NodeType::Element(data) => {
+            let child_ancestors = push_ancestor(ancestors, data, preceding_siblings);
+            let mut seen_siblings: Vec<&'a ElementData> = vec![];
+
+            root.children.iter().map(|child| {
+                let styled_child = style_tree_with_context(child, stylist, &child_ancestors, &reversed(&seen_siblings));
+
+                if let NodeType::Element(child_data) = &child.node_type {
+                    seen_siblings.push(child_data);
+                }
+
+                styled_child
+            }).collect()
+        }
+        NodeType::Text(_) => {
+            root.children.iter().map(|child| style_tree_with_context(child, stylist, ancestors, &[])).collect()
+        }
+    };
+
+    StyledNode { children, node: root, specified_values }
+}
+
+fn clone_ancestors<'a>(ancestors: &[AncestorContext<'a>]) -> Vec<AncestorContext<'a>> {
+    ancestors.iter().map(|anc| AncestorContext {
+        element: anc.element,
+        preceding_siblings: anc.preceding_siblings.clone(),
+    }).collect()
+}
+
+fn push_ancestor<'a>(
+    ancestors: &[AncestorContext<'a>],
+    element: &'a ElementData,
+    preceding_siblings: &[&'a ElementData],
+) -> Vec<AncestorContext<'a>> {
+    let mut chain = vec![AncestorContext { element, preceding_siblings: preceding_siblings.to_vec() }];
+    chain.extend(clone_ancestors(ancestors));
+    chain
+}
+
+/// `preceding_siblings` is built up in document order; contexts want it
+/// nearest-sibling-first, so reverse it when handing it to a child/context.
+fn reversed<'a>(siblings: &[&'a ElementData]) -> Vec<&'a ElementData> {
+    siblings.iter().rev().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{css::{Color, Parser as CssParser}, dom::{NodeType, Parser as DomParser}};
+
+    /// Finds the first element in the styled tree with the given tag name.
+    fn find<'a>(node: &'a StyledNode<'a>, tag: &str) -> Option<&'a StyledNode<'a>> {
+        if let NodeType::Element(data) = &node.node.node_type {
+            if data.tag_name == tag {
+                return Some(node);
+            }
         }
+
+        node.children.iter().find_map(|child| find(child, tag))
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn descendant_combinator_matches_non_adjacent_ancestor() {
+        let html = DomParser::parse("<div class=\"box\"><section><p>hi</p></section></div>".to_string());
+        let (sheet, errors) = CssParser::parse("div.box p { color: #ff0000; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let p = find(&tree, "p").expect("p node");
+
+        assert_eq!(p.specified_values.get("color"), Some(&Value::ColorValue(Color::new(255, 0, 0, 255))));
+    }
+
+    #[test]
+    fn child_combinator_rejects_grandparent_match() {
+        let html = DomParser::parse("<div class=\"box\"><section><p>hi</p></section></div>".to_string());
+        let (sheet, errors) = CssParser::parse("div.box > p { color: #ff0000; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let p = find(&tree, "p").expect("p node");
+
+        assert_eq!(p.specified_values.get("color"), None);
+    }
+
+    #[test]
+    fn adjacent_sibling_combinator_matches_immediately_preceding_sibling() {
+        let html = DomParser::parse("<div><h1>t</h1><p>a</p><p>b</p></div>".to_string());
+        let (sheet, errors) = CssParser::parse("h1 + p { color: #00ff00; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let div = find(&tree, "div").expect("div node");
+        let first_p = &div.children[1];
+        let second_p = &div.children[2];
+
+        assert_eq!(first_p.specified_values.get("color"), Some(&Value::ColorValue(Color::new(0, 255, 0, 255))));
+        assert_eq!(second_p.specified_values.get("color"), None);
+    }
+
+    #[test]
+    fn general_sibling_combinator_matches_any_preceding_sibling() {
+        let html = DomParser::parse("<div><h1>t</h1><span>s</span><p>a</p></div>".to_string());
+        let (sheet, errors) = CssParser::parse("h1 ~ p { color: #00ff00; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let div = find(&tree, "div").expect("div node");
+        let p = &div.children[2];
+
+        assert_eq!(p.specified_values.get("color"), Some(&Value::ColorValue(Color::new(0, 255, 0, 255))));
+    }
+
+    #[test]
+    fn specificity_sums_across_all_compounds() {
+        let html = DomParser::parse("<div id=\"outer\"><p class=\"a b\">x</p></div>".to_string());
+        let (sheet, errors) = CssParser::parse("#outer p.a.b { color: #ff0000; } p { color: #00ff00; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let p = find(&tree, "p").expect("p node");
+
+        assert_eq!(p.specified_values.get("color"), Some(&Value::ColorValue(Color::new(255, 0, 0, 255))));
+    }
+
+    #[test]
+    fn later_rule_wins_source_order_tie_break_at_equal_specificity() {
+        let html = DomParser::parse("<p>x</p>".to_string());
+        let (sheet, errors) = CssParser::parse("p { color: #ff0000; } p { color: #00ff00; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let p = find(&tree, "p").expect("p node");
+
+        assert_eq!(p.specified_values.get("color"), Some(&Value::ColorValue(Color::new(0, 255, 0, 255))));
+    }
+
+    #[test]
+    fn important_declaration_outranks_higher_specificity_normal_declaration() {
+        let html = DomParser::parse("<p id=\"x\">hi</p>".to_string());
+        let (sheet, errors) = CssParser::parse("p { color: #00ff00 !important; } #x { color: #ff0000; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let p = find(&tree, "p").expect("p node");
+
+        assert_eq!(p.specified_values.get("color"), Some(&Value::ColorValue(Color::new(0, 255, 0, 255))));
+    }
+
+    #[test]
+    fn author_origin_outranks_user_agent_origin_at_equal_specificity() {
+        let html = DomParser::parse("<p>hi</p>".to_string());
+        let (ua_sheet, ua_errors) = CssParser::parse_with_origin("p { color: #ff0000; }".to_string(), StylesheetOrigin::UserAgent);
+        assert!(ua_errors.is_empty());
+        let (sheet, errors) = StyleSheet::with_parent(std::rc::Rc::new(ua_sheet), "p { color: #00ff00; }".to_string());
+        assert!(errors.is_empty());
+        let stylist = Stylist::new(&sheet);
+        let tree = style_tree(&html, &stylist);
+        let p = find(&tree, "p").expect("p node");
+
+        assert_eq!(p.specified_values.get("color"), Some(&Value::ColorValue(Color::new(0, 255, 0, 255))));
+    }
+}